@@ -1,5 +1,17 @@
-struct Channel {
-    name: String,
-    id: i32,
-    handlers: Vec<Box<dyn Fn(&str) + Send>>,
+use crate::commands::ScriptedCommand;
+
+pub struct Channel {
+    pub name: String,
+    pub id: i32,
+    pub commands: Vec<ScriptedCommand>,
+}
+
+impl Channel {
+    pub fn new(name: impl Into<String>, id: i32) -> Self {
+        Channel {
+            name: name.into(),
+            id,
+            commands: Vec::new(),
+        }
+    }
 }
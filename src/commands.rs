@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rhai::{Engine, Scope, AST};
+use twitch_api::types::UserId;
+
+use crate::helix::HelixClient;
+
+/// A chat command backed by a Rhai script, keyed by the trigger that
+/// invokes it (e.g. `!so`, `!ban`). Cooldowns are declared at the top of the
+/// script as `const COOLDOWN_SECS = ...;` (shared by everyone) and
+/// `const USER_COOLDOWN_SECS = ...;` (per invoking user); either may be
+/// omitted and defaults to 0 (no cooldown).
+pub struct ScriptedCommand {
+    pub trigger: String,
+    ast: AST,
+    global_cooldown: Duration,
+    user_cooldown: Duration,
+}
+
+impl ScriptedCommand {
+    /// Loads every `*.rhai` file in `dir`. A command's trigger is its file
+    /// stem, e.g. `!so.rhai` becomes the trigger `!so`.
+    pub fn load_dir(dir: &Path, engine: &Engine) -> std::io::Result<Vec<ScriptedCommand>> {
+        let mut commands = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("rhai") {
+                continue;
+            }
+
+            let trigger = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default()
+                .to_string();
+            let source = fs::read_to_string(&path)?;
+            let ast = engine.compile(&source).expect("invalid command script");
+            // Read the cooldown constants off the compiled AST rather than
+            // running the script: `eval_ast` would execute the command body
+            // itself (including any moderation calls) at load time.
+            let literal = |name: &str| {
+                ast.iter_literal_variables(true, false)
+                    .find(|(var_name, ..)| *var_name == name)
+                    .and_then(|(_, _, value)| value.as_int().ok())
+                    .unwrap_or(0)
+                    .max(0) as u64
+            };
+
+            commands.push(ScriptedCommand {
+                trigger,
+                ast,
+                global_cooldown: Duration::from_secs(literal("COOLDOWN_SECS")),
+                user_cooldown: Duration::from_secs(literal("USER_COOLDOWN_SECS")),
+            });
+        }
+
+        Ok(commands)
+    }
+}
+
+/// Executes `ScriptedCommand`s, enforcing a global cooldown per command name
+/// and a per-user cooldown per `(user, command)` pair.
+pub struct CommandRunner {
+    engine: Engine,
+    global_cooldowns: HashMap<String, Instant>,
+    user_cooldowns: HashMap<(UserId, String), Instant>,
+}
+
+impl CommandRunner {
+    pub fn new(helix: Arc<HelixClient>) -> Self {
+        let mut engine = Engine::new();
+
+        let ban_helix = helix.clone();
+        engine.register_fn("ban", move |login: &str, reason: &str| {
+            let helix = ban_helix.clone();
+            let login = login.to_string();
+            let reason = reason.to_string();
+            tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(async move {
+                    if let Err(error) = helix.ban_user(&login, &reason).await {
+                        eprintln!("ban({login}) failed: {error}");
+                    }
+                })
+            });
+        });
+
+        let timeout_helix = helix.clone();
+        engine.register_fn("timeout", move |login: &str, seconds: i64, reason: &str| {
+            let helix = timeout_helix.clone();
+            let login = login.to_string();
+            let reason = reason.to_string();
+            tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(async move {
+                    if let Err(error) = helix
+                        .timeout_user(&login, Duration::from_secs(seconds.max(0) as u64), &reason)
+                        .await
+                    {
+                        eprintln!("timeout({login}) failed: {error}");
+                    }
+                })
+            });
+        });
+
+        let unban_helix = helix.clone();
+        engine.register_fn("unban", move |login: &str| {
+            let helix = unban_helix.clone();
+            let login = login.to_string();
+            tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(async move {
+                    if let Err(error) = helix.unban_user(&login).await {
+                        eprintln!("unban({login}) failed: {error}");
+                    }
+                })
+            });
+        });
+
+        let add_moderator_helix = helix.clone();
+        engine.register_fn("add_moderator", move |login: &str| {
+            let helix = add_moderator_helix.clone();
+            let login = login.to_string();
+            tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(async move {
+                    if let Err(error) = helix.add_moderator(&login).await {
+                        eprintln!("add_moderator({login}) failed: {error}");
+                    }
+                })
+            });
+        });
+
+        let remove_moderator_helix = helix.clone();
+        engine.register_fn("remove_moderator", move |login: &str| {
+            let helix = remove_moderator_helix.clone();
+            let login = login.to_string();
+            tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(async move {
+                    if let Err(error) = helix.remove_moderator(&login).await {
+                        eprintln!("remove_moderator({login}) failed: {error}");
+                    }
+                })
+            });
+        });
+
+        CommandRunner {
+            engine,
+            global_cooldowns: HashMap::new(),
+            user_cooldowns: HashMap::new(),
+        }
+    }
+
+    /// Runs `command` for `sender` unless its global or per-user cooldown is
+    /// still active, recording the invocation time on success. The two
+    /// cooldowns are independent: a command can e.g. allow anyone to trigger
+    /// it once every 5s globally while also limiting a given user to once
+    /// every 60s.
+    pub fn try_run(&mut self, command: &ScriptedCommand, sender: &UserId, sender_login: &str, message: &str) {
+        let now = Instant::now();
+
+        if let Some(last) = self.global_cooldowns.get(&command.trigger) {
+            if now.duration_since(*last) < command.global_cooldown {
+                return;
+            }
+        }
+
+        let user_key = (sender.clone(), command.trigger.clone());
+        if let Some(last) = self.user_cooldowns.get(&user_key) {
+            if now.duration_since(*last) < command.user_cooldown {
+                return;
+            }
+        }
+
+        let mut scope = Scope::new();
+        scope.push("message", message.to_string());
+        scope.push("sender", sender_login.to_string());
+
+        if let Err(error) = self.engine.run_ast_with_scope(&mut scope, &command.ast) {
+            eprintln!("command {} failed: {error}", command.trigger);
+            return;
+        }
+
+        self.global_cooldowns.insert(command.trigger.clone(), now);
+        self.user_cooldowns.insert(user_key, now);
+    }
+}
@@ -1,10 +1,19 @@
+use chrono::NaiveDateTime;
 use diesel::prelude::{Insertable, Queryable, Selectable};
 
 #[derive(Queryable, Selectable, Insertable)]
 #[diesel(table_name = crate::schema::access)]
 pub struct Access {
-    id: Option<i32>,
-    access_token: String,
-    refresh_token: String,
-    expires_in: Option<i32>,
+    pub id: Option<i32>,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::app_access)]
+pub struct AppAccess {
+    pub id: Option<i32>,
+    pub access_token: String,
+    pub expires_at: NaiveDateTime,
 }
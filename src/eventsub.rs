@@ -0,0 +1,230 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use hyper::{Body, Request, Response};
+use oauth2::Token;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::sync::Mutex;
+use twitch_api::types::UserId;
+
+use crate::token::AppAccessToken;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Twitch retries a notification delivery for up to an hour if it doesn't
+// get a 2xx back; anything older than that can never be a legitimate
+// duplicate, so it's safe to evict.
+const DEDUP_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Bounded, time-windowed set of seen message ids: old enough entries are
+/// evicted on insert so a long-running process doesn't grow this forever.
+struct Deduper {
+    order: VecDeque<(String, Instant)>,
+    ids: HashSet<String>,
+}
+
+impl Deduper {
+    fn new() -> Self {
+        Deduper {
+            order: VecDeque::new(),
+            ids: HashSet::new(),
+        }
+    }
+
+    /// Returns `true` if `id` had not been seen within the dedup window.
+    fn insert(&mut self, id: String) -> bool {
+        let now = Instant::now();
+        while let Some((_, seen_at)) = self.order.front() {
+            if now.duration_since(*seen_at) <= DEDUP_WINDOW {
+                break;
+            }
+            if let Some((old_id, _)) = self.order.pop_front() {
+                self.ids.remove(&old_id);
+            }
+        }
+
+        if !self.ids.insert(id.clone()) {
+            return false;
+        }
+        self.order.push_back((id, now));
+        true
+    }
+}
+
+/// Live state for the `stream.online` / `stream.offline` EventSub
+/// subscriptions, shared between the webhook HTTP handler and anything that
+/// wants to react to the broadcaster going live or offline.
+pub struct EventSubState {
+    secret: String,
+    live: AtomicBool,
+    seen_message_ids: Mutex<Deduper>,
+    handlers: Mutex<Vec<Box<dyn Fn(bool) + Send + Sync>>>,
+}
+
+impl EventSubState {
+    pub fn new(secret: impl Into<String>) -> Self {
+        EventSubState {
+            secret: secret.into(),
+            live: AtomicBool::new(false),
+            seen_message_ids: Mutex::new(Deduper::new()),
+            handlers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.live.load(Ordering::SeqCst)
+    }
+
+    pub async fn on_change(&self, handler: impl Fn(bool) + Send + Sync + 'static) {
+        self.handlers.lock().await.push(Box::new(handler));
+    }
+}
+
+/// Subscribes the broadcaster to `stream.online` and `stream.offline`,
+/// delivered to `callback_url` and signed with `state`'s secret.
+pub async fn subscribe(
+    app_token: &AppAccessToken,
+    broadcaster_id: &UserId,
+    callback_url: &str,
+    state: &EventSubState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client_id = std::env::var("TWITCH_CLIENT_ID").expect("TWITCH_CLIENT_ID not set");
+    let client = reqwest::Client::new();
+
+    for event_type in ["stream.online", "stream.offline"] {
+        let body = serde_json::json!({
+            "type": event_type,
+            "version": "1",
+            "condition": { "broadcaster_user_id": broadcaster_id.as_str() },
+            "transport": {
+                "method": "webhook",
+                "callback": callback_url,
+                "secret": state.secret,
+            },
+        });
+
+        let response = client
+            .post("https://api.twitch.tv/helix/eventsub/subscriptions")
+            .header("Client-Id", &client_id)
+            .bearer_auth(app_token.access_token().secret())
+            .json(&body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(format!(
+                "failed to subscribe to {event_type}: {}",
+                response.status()
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles `POST /eventsub/callback`: verifies the Twitch HMAC signature,
+/// answers verification challenges, deduplicates by message id, and flips
+/// the live flag (invoking registered handlers) on notification.
+pub async fn handle_callback(
+    req: Request<Body>,
+    state: Arc<EventSubState>,
+) -> Result<Response<Body>, hyper::Error> {
+    let message_id = header(&req, "Twitch-Eventsub-Message-Id");
+    let timestamp = header(&req, "Twitch-Eventsub-Message-Timestamp");
+    let signature = header(&req, "Twitch-Eventsub-Message-Signature");
+    let message_type = header(&req, "Twitch-Eventsub-Message-Type");
+
+    let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+
+    let (message_id, timestamp, signature, message_type) =
+        match (message_id, timestamp, signature, message_type) {
+            (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+            _ => return Ok(bad_request("missing EventSub headers")),
+        };
+
+    if !verify_signature(&state.secret, &message_id, &timestamp, &body_bytes, &signature) {
+        return Ok(bad_request("invalid signature"));
+    }
+
+    let payload: Value = match serde_json::from_slice(&body_bytes) {
+        Ok(value) => value,
+        Err(_) => return Ok(bad_request("invalid JSON")),
+    };
+
+    // Verification challenges must always be answered, even on a retried
+    // message id, so they're exempt from the dedup check below.
+    if message_type == "webhook_callback_verification" {
+        let challenge = payload["challenge"].as_str().unwrap_or_default().to_string();
+        return Ok(Response::new(Body::from(challenge)));
+    }
+
+    {
+        let mut seen = state.seen_message_ids.lock().await;
+        if !seen.insert(message_id) {
+            return Ok(Response::new(Body::empty()));
+        }
+    }
+
+    if message_type == "notification" {
+        let event_type = payload["subscription"]["type"].as_str().unwrap_or_default();
+        let live = match event_type {
+            "stream.online" => true,
+            "stream.offline" => false,
+            _ => return Ok(Response::new(Body::empty())),
+        };
+
+        state.live.store(live, Ordering::SeqCst);
+        for handler in state.handlers.lock().await.iter() {
+            handler(live);
+        }
+    }
+
+    Ok(Response::new(Body::empty()))
+}
+
+fn verify_signature(
+    secret: &str,
+    message_id: &str,
+    timestamp: &str,
+    body: &[u8],
+    signature: &str,
+) -> bool {
+    let Some(hex_signature) = signature.strip_prefix("sha256=") else {
+        return false;
+    };
+    let Ok(signature_bytes) = hex::decode(hex_signature) else {
+        return false;
+    };
+
+    let mut mac = match HmacSha256::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+
+    mac.update(message_id.as_bytes());
+    mac.update(timestamp.as_bytes());
+    mac.update(body);
+
+    // `verify_slice` compares in constant time, unlike `==` on the
+    // formatted hex strings.
+    mac.verify_slice(&signature_bytes).is_ok()
+}
+
+fn header(req: &Request<Body>, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+fn bad_request(message: &str) -> Response<Body> {
+    Response::builder()
+        .status(400)
+        .body(Body::from(message.to_string()))
+        .expect("Failed to build response")
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use twitch_api::helix::HelixClient as TwitchHelixClient;
+use twitch_api::types::UserId;
+
+use crate::token::get_token;
+
+/// Thin wrapper around `twitch_api`'s `HelixClient` that resolves a chatter's
+/// login to a `UserId` once and reuses it for every moderation call.
+///
+/// Every method re-reads the user token via `get_token()` rather than
+/// holding a snapshot, so a token refreshed mid-session (or by the IRC
+/// subsystem reconnecting) is picked up automatically instead of going
+/// stale and failing with 401s.
+pub struct HelixClient {
+    client: TwitchHelixClient<'static, reqwest::Client>,
+    broadcaster_id: UserId,
+    moderator_id: UserId,
+    user_ids: Mutex<HashMap<String, UserId>>,
+}
+
+impl HelixClient {
+    pub fn new(broadcaster_id: UserId, moderator_id: UserId) -> Self {
+        HelixClient {
+            client: TwitchHelixClient::default(),
+            broadcaster_id,
+            moderator_id,
+            user_ids: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn resolve_user_id(&self, login: &str) -> Result<UserId, Box<dyn std::error::Error>> {
+        if let Some(id) = self.user_ids.lock().await.get(login) {
+            return Ok(id.clone());
+        }
+
+        let token = get_token().await?;
+        let user = self
+            .client
+            .get_user_from_login(login, &token)
+            .await?
+            .ok_or_else(|| format!("no such user: {login}"))?;
+
+        self.user_ids
+            .lock()
+            .await
+            .insert(login.to_string(), user.id.clone());
+
+        Ok(user.id)
+    }
+
+    pub async fn ban_user(&self, login: &str, reason: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let user_id = self.resolve_user_id(login).await?;
+        let token = get_token().await?;
+        self.client
+            .ban_user(
+                user_id,
+                reason,
+                None,
+                &self.broadcaster_id,
+                &self.moderator_id,
+                &token,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn timeout_user(
+        &self,
+        login: &str,
+        duration: Duration,
+        reason: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let user_id = self.resolve_user_id(login).await?;
+        let token = get_token().await?;
+        self.client
+            .ban_user(
+                user_id,
+                reason,
+                Some(duration),
+                &self.broadcaster_id,
+                &self.moderator_id,
+                &token,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn unban_user(&self, login: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let user_id = self.resolve_user_id(login).await?;
+        let token = get_token().await?;
+        self.client
+            .unban_user(user_id, &self.broadcaster_id, &self.moderator_id, &token)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn add_moderator(&self, login: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let user_id = self.resolve_user_id(login).await?;
+        let token = get_token().await?;
+        self.client
+            .add_channel_moderator(&self.broadcaster_id, &user_id, &token)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_moderator(&self, login: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let user_id = self.resolve_user_id(login).await?;
+        let token = get_token().await?;
+        self.client
+            .remove_channel_moderator(&self.broadcaster_id, &user_id, &token)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use oauth2::Token;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tokio_native_tls::TlsConnector;
+use twitch_api::types::UserId;
+
+use crate::channels::Channel;
+use crate::commands::CommandRunner;
+use crate::token::get_token;
+
+const IRC_HOST: &str = "irc.chat.twitch.tv";
+const IRC_PORT: u16 = 6697;
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Connects to Twitch IRC, joins every `channels` entry and dispatches each
+/// PRIVMSG to that channel's matching scripted command. Runs until the
+/// process is killed, reconnecting with exponential backoff whenever the
+/// connection drops.
+pub async fn run(
+    login: &str,
+    mut channels: Vec<Channel>,
+    runner: &mut CommandRunner,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut backoff = Duration::from_secs(1);
+
+    loop {
+        match connect_once(login, &mut channels, runner).await {
+            Ok(()) => backoff = Duration::from_secs(1),
+            Err(error) => {
+                eprintln!("IRC connection lost ({error}), reconnecting in {backoff:?}");
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+async fn connect_once(
+    login: &str,
+    channels: &mut [Channel],
+    runner: &mut CommandRunner,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let token = get_token().await?;
+
+    let tcp = TcpStream::connect((IRC_HOST, IRC_PORT)).await?;
+    let connector = TlsConnector::from(native_tls::TlsConnector::new()?);
+    let stream = connector.connect(IRC_HOST, tcp).await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    writer
+        .write_all(format!("PASS oauth:{}\r\n", token.access_token().secret()).as_bytes())
+        .await?;
+    writer.write_all(format!("NICK {login}\r\n").as_bytes()).await?;
+    writer
+        .write_all(b"CAP REQ :twitch.tv/membership twitch.tv/commands twitch.tv/tags\r\n")
+        .await?;
+    for channel in channels.iter() {
+        writer
+            .write_all(format!("JOIN #{}\r\n", channel.name).as_bytes())
+            .await?;
+    }
+
+    while let Some(line) = lines.next_line().await? {
+        if let Some(rest) = line.strip_prefix("PING ") {
+            writer.write_all(format!("PONG {rest}\r\n").as_bytes()).await?;
+            continue;
+        }
+
+        if let Some(privmsg) = parse_privmsg(&line) {
+            let trigger = privmsg.text.split_whitespace().next().unwrap_or_default();
+
+            if let Some(channel) = channels.iter().find(|c| c.name == privmsg.channel) {
+                if let Some(command) = channel.commands.iter().find(|c| c.trigger == trigger) {
+                    runner.try_run(command, &privmsg.sender_id, &privmsg.sender_login, &privmsg.text);
+                }
+            }
+        }
+    }
+
+    Err("IRC stream closed by server".into())
+}
+
+struct PrivMsg {
+    channel: String,
+    sender_login: String,
+    sender_id: UserId,
+    text: String,
+}
+
+fn parse_privmsg(line: &str) -> Option<PrivMsg> {
+    // @user-id=123;... :nick!user@host PRIVMSG #channel :message text
+    let (tags, rest) = match line.split_once(' ') {
+        Some((tags, rest)) if tags.starts_with('@') => (Some(&tags[1..]), rest),
+        _ => (None, line),
+    };
+
+    let (prefix, rest) = rest.split_once(" PRIVMSG #")?;
+    let (channel, text) = rest.split_once(" :")?;
+    let sender_login = prefix
+        .trim_start_matches(':')
+        .split('!')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let sender_id = tags
+        .and_then(|tags| tags.split(';').find_map(|tag| tag.strip_prefix("user-id=")))
+        .unwrap_or_default();
+
+    Some(PrivMsg {
+        channel: channel.to_string(),
+        sender_login,
+        sender_id: UserId::from(sender_id.to_string()),
+        text: text.to_string(),
+    })
+}
@@ -1,10 +1,25 @@
+pub mod channels;
+pub mod commands;
 pub mod db;
+pub mod eventsub;
+pub mod helix;
+pub mod irc;
 pub mod schema;
 pub mod token;
 
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
 use dotenv::dotenv;
+use rhai::Engine;
+use twitch_api::types::UserId;
 
-use crate::token::get_token;
+use crate::channels::Channel;
+use crate::commands::{CommandRunner, ScriptedCommand};
+use crate::eventsub::EventSubState;
+use crate::helix::HelixClient;
+use crate::token::{get_app_token, get_token, set_eventsub_state, spawn_http_server};
 
 #[macro_use]
 extern crate lazy_static;
@@ -15,9 +30,59 @@ async fn main() {
 
     println!("Bot is starting");
 
+    // The webhook secret must be in place before the hyper server can
+    // possibly receive a callback, so this has to happen before the server
+    // is started below.
+    let eventsub_state = Arc::new(EventSubState::new(
+        env::var("EVENTSUB_SECRET").expect("EVENTSUB_SECRET not set"),
+    ));
+    set_eventsub_state(eventsub_state.clone());
+
+    // Runs unconditionally and for the life of the process: EventSub
+    // notifications arrive on this port whether or not an interactive auth
+    // flow ever needs it too.
+    spawn_http_server();
+
     println!("Starting credentials server");
 
     let token_response = get_token().await.unwrap();
 
     println!("{:?}", token_response);
+
+    let broadcaster_id = UserId::from(
+        env::var("TWITCH_BROADCASTER_ID").expect("TWITCH_BROADCASTER_ID not set"),
+    );
+
+    let app_token = get_app_token().await.unwrap();
+    let callback_url = format!(
+        "{}/eventsub/callback",
+        env::var("HOSTNAME_URL").expect("HOSTNAME_URL not set")
+    );
+    if let Err(error) =
+        eventsub::subscribe(&app_token, &broadcaster_id, &callback_url, &eventsub_state).await
+    {
+        eprintln!("Failed to subscribe to EventSub: {error}");
+    }
+
+    let helix = Arc::new(HelixClient::new(
+        broadcaster_id,
+        UserId::from(env::var("TWITCH_BOT_USER_ID").expect("TWITCH_BOT_USER_ID not set")),
+    ));
+
+    let engine = Engine::new();
+    let commands = ScriptedCommand::load_dir(Path::new("commands"), &engine)
+        .expect("failed to load commands");
+
+    let mut channel = Channel::new(
+        env::var("TWITCH_CHANNEL").expect("TWITCH_CHANNEL not set"),
+        0,
+    );
+    channel.commands = commands;
+
+    let login = env::var("TWITCH_BOT_LOGIN").expect("TWITCH_BOT_LOGIN not set");
+    let mut runner = CommandRunner::new(helix);
+
+    if let Err(error) = irc::run(&login, vec![channel], &mut runner).await {
+        eprintln!("IRC subsystem exited: {error}");
+    }
 }
@@ -12,7 +12,7 @@ use tokio::sync::mpsc;
 use twitch_api::twitch_oauth2::{Scope as TwitchScope, UserTokenBuilder};
 use url::Url;
 
-use crate::db::{establish_connection, Access};
+use crate::db::{establish_connection, Access, AppAccess};
 use crate::schema::access::access_token;
 extern crate url;
 use diesel::prelude::*;
@@ -27,6 +27,47 @@ lazy_static! {
         TwitchScope::ModeratorManageBannedUsers,
         TwitchScope::UserReadEmail,
     ];
+
+    // Set once at startup via `set_eventsub_state` so the webhook route on
+    // the hyper server below has somewhere to deliver notifications.
+    static ref EVENTSUB: std::sync::Mutex<Option<std::sync::Arc<crate::eventsub::EventSubState>>> =
+        std::sync::Mutex::new(None);
+
+    // Set by `start_server` for the duration of an interactive auth flow so
+    // `/auth/callback` has a CSRF token to check and a channel to deliver
+    // the resulting `TwitchToken` on.
+    static ref OAUTH: std::sync::Mutex<Option<OAuthState>> = std::sync::Mutex::new(None);
+}
+
+#[derive(Clone)]
+struct OAuthState {
+    expected_csrf_state: String,
+    sender: mpsc::Sender<TwitchToken>,
+}
+
+pub fn set_eventsub_state(state: std::sync::Arc<crate::eventsub::EventSubState>) {
+    *EVENTSUB.lock().unwrap() = Some(state);
+}
+
+/// Binds the webhook receiver (`/auth/callback`, `/eventsub/callback`) and
+/// runs it for the lifetime of the process. Must be called once from `main`
+/// regardless of whether interactive auth is needed this run, since
+/// EventSub notifications (and a future token refresh's auth flow) depend
+/// on something listening on this port at all times.
+pub fn spawn_http_server() {
+    let make_svc = make_service_fn(move |_conn| {
+        let service = service_fn(handle_request);
+        async { Ok::<_, hyper::Error>(service) }
+    });
+
+    let addr = ([127, 0, 0, 1], 3000).into();
+    let server = Server::bind(&addr).serve(make_svc);
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("server error: {}", e);
+        }
+    });
 }
 
 #[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
@@ -68,7 +109,52 @@ impl Token for TwitchToken {
     }
 }
 
-pub async fn get_token() -> Result<TwitchToken, diesel::result::Error> {
+/// A client-credentials ("app access") token, used for server-to-server
+/// Helix and EventSub calls that aren't made on behalf of a specific user.
+/// Unlike `TwitchToken`, Twitch never issues a refresh token for these —
+/// an expired one is simply replaced by requesting a new one.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct AppAccessToken {
+    access_token: AccessToken,
+    token_type: TokenType,
+    expires_at: NaiveDateTime,
+}
+
+impl Token for AppAccessToken {
+    fn access_token(&self) -> &AccessToken {
+        &self.access_token
+    }
+
+    fn token_type(&self) -> &TokenType {
+        &self.token_type
+    }
+
+    fn expires_in(&self) -> Option<Duration> {
+        let now = Utc::now().naive_utc();
+        if self.expires_at > now {
+            Some(Duration::new(
+                (self.expires_at - now).num_seconds().unsigned_abs(),
+                0,
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn refresh_token(&self) -> Option<&RefreshToken> {
+        None
+    }
+
+    fn scopes(&self) -> Option<&Vec<Scope>> {
+        None
+    }
+}
+
+// Refresh slightly before Twitch actually expires the token so a request
+// in flight doesn't race the expiry and come back as a 401.
+const EXPIRY_SKEW: chrono::Duration = chrono::Duration::seconds(60);
+
+pub async fn get_token() -> Result<TwitchToken, Box<dyn std::error::Error>> {
     use crate::schema::access::dsl::access;
 
     let connection = &mut establish_connection();
@@ -77,9 +163,21 @@ pub async fn get_token() -> Result<TwitchToken, diesel::result::Error> {
 
     match credentials {
         Ok(value) => {
-            // if value.expires_at > Utc::now().naive_utc() {
-            //     Ok(refresh_token(value))
-            // }
+            if value.expires_at - EXPIRY_SKEW <= Utc::now().naive_utc() {
+                return match refresh_token(&value).await {
+                    Ok(token) => Ok(token),
+                    // The refresh token itself was rejected (revoked/invalid) —
+                    // nothing short of re-authorizing will fix that.
+                    Err(RefreshFailure::InvalidGrant(reason)) => {
+                        eprintln!("refresh token rejected ({reason}), falling back to interactive auth");
+                        Ok(start_server().await?)
+                    }
+                    // A network blip or a 5xx from Twitch: let the caller
+                    // (e.g. the IRC reconnect loop) retry with backoff
+                    // instead of dropping into the interactive flow.
+                    Err(RefreshFailure::Transient(error)) => Err(error),
+                };
+            }
 
             Ok(TwitchToken {
                 access_token: AccessToken::from(value.access_token),
@@ -93,13 +191,102 @@ pub async fn get_token() -> Result<TwitchToken, diesel::result::Error> {
                     .collect(),
             })
         }
-        Err(_error) => Ok(start_server().await.unwrap()),
+        Err(_error) => Ok(start_server().await?),
+    }
+}
+
+/// Why a refresh attempt failed, so callers can tell a dead refresh token
+/// (re-auth needed) apart from a transient network/server error (retry).
+enum RefreshFailure {
+    InvalidGrant(String),
+    Transient(Box<dyn std::error::Error>),
+}
+
+impl std::fmt::Display for RefreshFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshFailure::InvalidGrant(reason) => write!(f, "invalid grant: {reason}"),
+            RefreshFailure::Transient(error) => write!(f, "transient error: {error}"),
+        }
+    }
+}
+
+impl std::fmt::Debug for RefreshFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
     }
 }
 
-// fn refresh_token(value: Access) -> _ {
-//     todo!()
-// }
+impl std::error::Error for RefreshFailure {}
+
+async fn refresh_token(value: &Access) -> Result<TwitchToken, RefreshFailure> {
+    let client_id = env::var("TWITCH_CLIENT_ID").expect("TWITCH_CLIENT_ID not set");
+    let client_secret = env::var("TWITCH_CLIENT_SECRET").expect("TWITCH_CLIENT_SECRET not set");
+
+    let mut params = HashMap::new();
+    params.insert("client_id", client_id);
+    params.insert("client_secret", client_secret);
+    params.insert("grant_type", "refresh_token".to_string());
+    params.insert("refresh_token", value.refresh_token.clone());
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post("https://id.twitch.tv/oauth2/token")
+        .form(&params)
+        .send()
+        .await
+        .map_err(|error| RefreshFailure::Transient(error.into()))?;
+
+    // Twitch rejects a revoked/invalid refresh token with 400 (invalid_grant)
+    // or 401; anything else (5xx, rate limiting) is worth retrying instead.
+    if response.status() == reqwest::StatusCode::BAD_REQUEST
+        || response.status() == reqwest::StatusCode::UNAUTHORIZED
+    {
+        let body = response.text().await.unwrap_or_default();
+        return Err(RefreshFailure::InvalidGrant(body));
+    }
+
+    if !response.status().is_success() {
+        return Err(RefreshFailure::Transient(
+            format!("refresh failed with status {}", response.status()).into(),
+        ));
+    }
+
+    let data: Value = response
+        .json()
+        .await
+        .map_err(|error| RefreshFailure::Transient(error.into()))?;
+
+    let access_token = data["access_token"]
+        .as_str()
+        .ok_or_else(|| RefreshFailure::Transient("access_token missing from refresh response".into()))?
+        .to_string();
+    // Twitch rotates the refresh token on every use, so the old one must be
+    // discarded in favor of whatever comes back here.
+    let new_refresh_token = data["refresh_token"]
+        .as_str()
+        .ok_or_else(|| RefreshFailure::Transient("refresh_token missing from refresh response".into()))?
+        .to_string();
+    let expires_in = data["expires_in"]
+        .as_i64()
+        .ok_or_else(|| RefreshFailure::Transient("expires_in missing from refresh response".into()))?;
+
+    let token = TwitchToken {
+        access_token: AccessToken::from(access_token),
+        refresh_token: RefreshToken::from(new_refresh_token),
+        expires_at: Utc::now().naive_utc() + chrono::Duration::seconds(expires_in),
+        token_type: TokenType::Bearer,
+        scope: SCOPES
+            .to_vec()
+            .into_iter()
+            .map(|scope| Scope::from(scope.to_string()))
+            .collect(),
+    };
+
+    update_credentials(token.clone()).map_err(|error| RefreshFailure::Transient(error.into()))?;
+
+    Ok(token)
+}
 
 pub fn update_credentials(token: TwitchToken) -> Result<(), diesel::result::Error> {
     use crate::schema::access::dsl::{access, expires_at, id, refresh_token};
@@ -139,6 +326,100 @@ pub fn update_credentials(token: TwitchToken) -> Result<(), diesel::result::Erro
     Ok(())
 }
 
+/// Loads the app access token, fetching a fresh one via the client
+/// credentials grant if none is stored or the stored one has expired.
+pub async fn get_app_token() -> Result<AppAccessToken, Box<dyn std::error::Error>> {
+    use crate::schema::app_access::dsl::app_access;
+
+    let connection = &mut establish_connection();
+    let credentials = app_access.first::<AppAccess>(connection);
+
+    if let Ok(value) = credentials {
+        if value.expires_at - EXPIRY_SKEW > Utc::now().naive_utc() {
+            return Ok(AppAccessToken {
+                access_token: AccessToken::from(value.access_token),
+                token_type: TokenType::Bearer,
+                expires_at: value.expires_at,
+            });
+        }
+    }
+
+    request_app_token().await
+}
+
+async fn request_app_token() -> Result<AppAccessToken, Box<dyn std::error::Error>> {
+    let client_id = env::var("TWITCH_CLIENT_ID").expect("TWITCH_CLIENT_ID not set");
+    let client_secret = env::var("TWITCH_CLIENT_SECRET").expect("TWITCH_CLIENT_SECRET not set");
+
+    let mut params = HashMap::new();
+    params.insert("client_id", client_id);
+    params.insert("client_secret", client_secret);
+    params.insert("grant_type", "client_credentials".to_string());
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post("https://id.twitch.tv/oauth2/token")
+        .form(&params)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(format!("app token request failed with status {}", response.status()).into());
+    }
+
+    let data: Value = response.json().await?;
+
+    let token = AppAccessToken {
+        access_token: AccessToken::from(
+            data["access_token"]
+                .as_str()
+                .expect("access_token not found")
+                .to_string(),
+        ),
+        expires_at: Utc::now().naive_utc()
+            + chrono::Duration::seconds(data["expires_in"].as_i64().unwrap()),
+        token_type: TokenType::Bearer,
+    };
+
+    update_app_credentials(&token)?;
+
+    Ok(token)
+}
+
+fn update_app_credentials(token: &AppAccessToken) -> Result<(), diesel::result::Error> {
+    use crate::schema::app_access::dsl::{access_token, app_access, expires_at, id};
+
+    let connection = &mut establish_connection();
+
+    let not_exists: bool = app_access
+        .filter(id.eq(1))
+        .limit(1)
+        .load::<AppAccess>(connection)?
+        .is_empty();
+
+    if not_exists {
+        diesel::insert_into(app_access)
+            .values((
+                id.eq(1),
+                access_token.eq(token.access_token.to_string()),
+                expires_at.eq(token.expires_at),
+            ))
+            .execute(connection)?;
+
+        println!("New app access added to the database");
+    } else {
+        diesel::update(app_access.filter(id.eq(1)))
+            .set((
+                access_token.eq(token.access_token.to_string()),
+                expires_at.eq(token.expires_at),
+            ))
+            .execute(connection)?;
+        println!("App access updated on the database");
+    }
+
+    Ok(())
+}
+
 async fn create_token_params(code: Option<String>) -> HashMap<&'static str, String> {
     let client_id = env::var("TWITCH_CLIENT_ID").expect("TWITCH_CLIENT_ID not set");
     let client_secret = env::var("TWITCH_CLIENT_SECRET").expect("TWITCH_CLIENT_SECRET not set");
@@ -157,9 +438,12 @@ async fn create_token_params(code: Option<String>) -> HashMap<&'static str, Stri
     params
 }
 
+// The caller (`handle_request`) has already checked `state` against the
+// registered `OAuthState` and only takes it out of `OAUTH` on a match, so
+// by the time this runs the CSRF check has passed.
 async fn auth_callback(
     code: Option<String>,
-    tx: mpsc::Sender<TwitchToken>,
+    oauth: &OAuthState,
 ) -> Result<Response<Body>, hyper::Error> {
     let params = create_token_params(code).await;
     let client = reqwest::Client::new();
@@ -195,7 +479,7 @@ async fn auth_callback(
                     .collect(),
             };
             update_credentials(token.clone()).unwrap();
-            tx.send(token).await.expect("Failed to send tokens");
+            oauth.sender.send(token).await.expect("Failed to send tokens");
             Ok(Response::new(Body::from(
                 "Authentication was successful! You can close this window now.",
             )))
@@ -207,19 +491,13 @@ async fn auth_callback(
     }
 }
 
+/// Runs the interactive authorization-code flow: generates the Twitch
+/// authorize URL, registers this flow's CSRF token and a one-shot channel
+/// in `OAUTH` so the already-running HTTP server (see `spawn_http_server`)
+/// can deliver the resulting token, then waits for it.
 pub async fn start_server() -> Result<TwitchToken, Box<dyn std::error::Error>> {
     let (sender, mut receiver) = mpsc::channel(1);
 
-    // Create a service function to handle incoming requests
-    let make_svc = make_service_fn(move |_conn| {
-        let sender_clone = sender.clone();
-        let service = service_fn(move |req| handle_request(req, sender_clone.clone()));
-        async { Ok::<_, hyper::Error>(service) }
-    });
-
-    let addr = ([127, 0, 0, 1], 3000).into();
-    let server = Server::bind(&addr).serve(make_svc);
-
     let client_id = env::var("TWITCH_CLIENT_ID").unwrap_or_default();
     let client_secret = env::var("TWITCH_CLIENT_SECRET").unwrap_or_default();
     let redirect_url = Url::parse(&format!(
@@ -230,17 +508,15 @@ pub async fn start_server() -> Result<TwitchToken, Box<dyn std::error::Error>> {
 
     let mut builder = UserTokenBuilder::new(client_id, client_secret, redirect_url);
     builder = builder.set_scopes(SCOPES.to_vec());
-    let (url, _csrf_token) = builder.generate_url();
-
-    println!("Open {} to get your Twitch token", url.to_string());
+    let (url, csrf_token) = builder.generate_url();
 
-    // Start the server in a separate Tokio task
-    tokio::spawn(async move {
-        if let Err(e) = server.await {
-            eprintln!("server error: {}", e);
-        }
+    *OAUTH.lock().unwrap() = Some(OAuthState {
+        expected_csrf_state: csrf_token.secret().to_string(),
+        sender,
     });
 
+    println!("Open {} to get your Twitch token", url.to_string());
+
     // Wait for the token from the receiver
     let token = receiver
         .recv()
@@ -250,17 +526,45 @@ pub async fn start_server() -> Result<TwitchToken, Box<dyn std::error::Error>> {
     Ok(token)
 }
 
-async fn handle_request(
-    req: Request<Body>,
-    token_sender: mpsc::Sender<TwitchToken>,
-) -> Result<Response<Body>, hyper::Error> {
+async fn handle_request(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
     match (req.method(), req.uri().path()) {
         (&hyper::Method::GET, "/auth/callback") => {
             let query = req.uri().query().unwrap_or_default();
             let params: HashMap<_, _> = url::form_urlencoded::parse(query.as_bytes())
                 .into_owned()
                 .collect();
-            auth_callback(params.get("code").cloned(), token_sender).await
+
+            // Peek at the registered flow without consuming it: a
+            // CSRF-mismatched request (stray or forged) must not be able to
+            // steal/drop the `OAuthState` out from under a real auth flow
+            // still in progress.
+            let oauth = OAUTH.lock().unwrap().clone();
+            match oauth {
+                Some(oauth) if params.get("state").map(String::as_str) == Some(oauth.expected_csrf_state.as_str()) =>
+                {
+                    // Only now is the flow actually consumed, so a second
+                    // callback for the same flow can't also succeed.
+                    OAUTH.lock().unwrap().take();
+                    auth_callback(params.get("code").cloned(), &oauth).await
+                }
+                Some(_) => Ok(Response::builder()
+                    .status(400)
+                    .body(Body::from("CSRF state mismatch"))
+                    .expect("Failed to build response")),
+                None => Ok(Response::builder()
+                    .status(503)
+                    .body(Body::from("No auth flow in progress"))
+                    .expect("Failed to build response")),
+            }
+        }
+        (&hyper::Method::POST, "/eventsub/callback") => {
+            match EVENTSUB.lock().unwrap().clone() {
+                Some(state) => crate::eventsub::handle_callback(req, state).await,
+                None => Ok(Response::builder()
+                    .status(503)
+                    .body(Body::from("EventSub not configured"))
+                    .expect("Failed to build response")),
+            }
         }
         _ => Ok(Response::new(Body::from("Not Found"))),
     }